@@ -0,0 +1,145 @@
+use std::io::Cursor;
+
+use anyhow::Result;
+use log::warn;
+use rusqlite::params;
+use rusqlite::session::{ConflictAction, Session};
+use rusqlite::Connection;
+
+use crate::sqlite::DBPool;
+
+// `run` and the changeset insert below land in two separate SQLite files
+// (`test.db` and `log.db`), so they can't share a transaction. Treat the
+// audit record as best-effort: once a statement inside `run` has committed,
+// a failure to log it must not turn into an `Err` that looks like the write
+// itself failed and invites callers to retry/duplicate a mutation that
+// already landed. Log the changeset whether `run` succeeds or fails - if
+// `run` issues statements directly against `connection` rather than through
+// its own transaction, earlier statements can already be committed even
+// though `run` as a whole returns `Err`, and those writes still belong in
+// the audit trail.
+pub fn with_audit<T>(
+  connection: &Connection,
+  log_pool: &DBPool,
+  table_name: &str,
+  operation: &str,
+  run: impl FnOnce() -> Result<T>,
+) -> Result<T> {
+  let mut session = Session::new(connection)?;
+  session.attach(Some(table_name))?;
+
+  let result = run();
+
+  if let Err(err) = log_changeset(&mut session, log_pool, table_name, operation) {
+    warn!("Failed to record audit changeset for table {table_name}: {err}");
+  }
+
+  result
+}
+
+fn log_changeset(
+  session: &mut Session,
+  log_pool: &DBPool,
+  table_name: &str,
+  operation: &str,
+) -> Result<()> {
+  let mut changeset = Vec::new();
+  session.changeset_strm(&mut changeset)?;
+
+  if !changeset.is_empty() {
+    let log_connection = log_pool.get()?;
+    log_connection.execute(
+      "INSERT INTO changesets (operation, table_name, created_at, changeset) VALUES (?1, ?2, unixepoch(), ?3)",
+      params![operation, table_name, changeset],
+    )?;
+  }
+
+  Ok(())
+}
+
+// Applies a stored changeset blob to `connection`, replaying the writes it
+// captured. Conflicting changes are skipped rather than aborting the whole
+// replay, so a partially-diverged target still picks up what it can.
+pub fn replay_changeset(connection: &Connection, changeset: &[u8]) -> Result<()> {
+  connection.apply_strm(
+    &mut Cursor::new(changeset),
+    None::<fn(&str) -> bool>,
+    |_conflict_type, _item| ConflictAction::SQLITE_CHANGESET_OMIT,
+  )?;
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use anyhow::anyhow;
+
+  fn memory_log_pool() -> DBPool {
+    let manager = r2d2_sqlite::SqliteConnectionManager::memory();
+    let pool = r2d2::Pool::builder().max_size(1).build(manager).unwrap();
+    pool
+      .get()
+      .unwrap()
+      .execute_batch(
+        "CREATE TABLE changesets (
+          id INTEGER PRIMARY KEY AUTOINCREMENT,
+          operation TEXT NOT NULL,
+          table_name TEXT NOT NULL,
+          created_at INTEGER NOT NULL,
+          changeset BLOB NOT NULL
+        )",
+      )
+      .unwrap();
+    pool
+  }
+
+  #[test]
+  fn with_audit_logs_the_changeset_even_when_run_fails_after_a_partial_write() {
+    let connection = Connection::open_in_memory().unwrap();
+    connection
+      .execute_batch("CREATE TABLE t (id INTEGER PRIMARY KEY, value TEXT)")
+      .unwrap();
+    let log_pool = memory_log_pool();
+
+    let result: Result<()> = with_audit(&connection, &log_pool, "t", "insert", || {
+      connection.execute("INSERT INTO t (id, value) VALUES (1, 'partial')", [])?;
+      Err(anyhow!("downstream failure after the write already committed"))
+    });
+    assert!(result.is_err());
+
+    let row_count: i64 = connection
+      .query_row("SELECT COUNT(*) FROM t", [], |row| row.get(0))
+      .unwrap();
+    assert_eq!(row_count, 1, "the partial write should still be committed");
+
+    let log_connection = log_pool.get().unwrap();
+    let changeset_count: i64 = log_connection
+      .query_row("SELECT COUNT(*) FROM changesets", [], |row| row.get(0))
+      .unwrap();
+    assert_eq!(
+      changeset_count, 1,
+      "the partial write must still show up in the audit trail"
+    );
+  }
+
+  #[test]
+  fn with_audit_logs_the_changeset_on_success() {
+    let connection = Connection::open_in_memory().unwrap();
+    connection
+      .execute_batch("CREATE TABLE t (id INTEGER PRIMARY KEY, value TEXT)")
+      .unwrap();
+    let log_pool = memory_log_pool();
+
+    with_audit(&connection, &log_pool, "t", "insert", || {
+      connection.execute("INSERT INTO t (id, value) VALUES (1, 'ok')", [])?;
+      Ok(())
+    })
+    .unwrap();
+
+    let log_connection = log_pool.get().unwrap();
+    let changeset_count: i64 = log_connection
+      .query_row("SELECT COUNT(*) FROM changesets", [], |row| row.get(0))
+      .unwrap();
+    assert_eq!(changeset_count, 1);
+  }
+}
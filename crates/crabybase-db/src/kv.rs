@@ -0,0 +1,105 @@
+use anyhow::Result;
+use rusqlite::params;
+
+use crate::sqlite::DBPool;
+
+pub fn get(pool: &DBPool, key: &str) -> Result<Option<Vec<u8>>> {
+  let connection = pool.get()?;
+  let mut statement = connection.prepare("SELECT value FROM objects WHERE key = ?1")?;
+  let mut rows = statement.query(params![key])?;
+
+  match rows.next()? {
+    Some(row) => Ok(Some(row.get(0)?)),
+    None => Ok(None),
+  }
+}
+
+pub fn set(pool: &DBPool, key: &str, value: &[u8]) -> Result<()> {
+  let connection = pool.get()?;
+  connection.execute(
+    r#"
+      INSERT INTO objects (key, value, updated_at)
+      VALUES (?1, ?2, unixepoch())
+      ON CONFLICT(key) DO UPDATE SET value = excluded.value, updated_at = excluded.updated_at
+    "#,
+    params![key, value],
+  )?;
+  Ok(())
+}
+
+pub fn delete(pool: &DBPool, key: &str) -> Result<()> {
+  let connection = pool.get()?;
+  connection.execute("DELETE FROM objects WHERE key = ?1", params![key])?;
+  Ok(())
+}
+
+pub fn list(pool: &DBPool, prefix: &str) -> Result<Vec<String>> {
+  let connection = pool.get()?;
+  let mut statement = connection
+    .prepare("SELECT key FROM objects WHERE key LIKE ?1 || '%' ESCAPE '\\' ORDER BY key")?;
+  let mut rows = statement.query(params![escape_like_pattern(prefix)])?;
+
+  let mut keys = vec![];
+  while let Some(row) = rows.next()? {
+    keys.push(row.get(0)?);
+  }
+  Ok(keys)
+}
+
+// Escapes `\`, `%` and `_` so a caller's prefix is matched literally instead
+// of as a LIKE pattern - a key containing `_` or `%` would otherwise match
+// unrelated keys that merely differ at that position.
+fn escape_like_pattern(pattern: &str) -> String {
+  pattern
+    .replace('\\', "\\\\")
+    .replace('%', "\\%")
+    .replace('_', "\\_")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn pool_with_objects_table() -> DBPool {
+    let manager = r2d2_sqlite::SqliteConnectionManager::memory();
+    let pool = r2d2::Pool::builder().max_size(1).build(manager).unwrap();
+    pool
+      .get()
+      .unwrap()
+      .execute_batch(
+        "CREATE TABLE objects (
+          key TEXT PRIMARY KEY,
+          value BLOB NOT NULL,
+          updated_at INTEGER NOT NULL
+        )",
+      )
+      .unwrap();
+    pool
+  }
+
+  #[test]
+  fn list_treats_underscore_in_prefix_literally() {
+    let pool = pool_with_objects_table();
+    set(&pool, "user_123", b"a").unwrap();
+    set(&pool, "userX123", b"b").unwrap();
+    set(&pool, "user_123abc", b"c").unwrap();
+
+    let keys = list(&pool, "user_123").unwrap();
+    assert_eq!(keys, vec!["user_123", "user_123abc"]);
+  }
+
+  #[test]
+  fn get_set_delete_round_trip() {
+    let pool = pool_with_objects_table();
+    assert_eq!(get(&pool, "k").unwrap(), None);
+
+    set(&pool, "k", b"v1").unwrap();
+    assert_eq!(get(&pool, "k").unwrap(), Some(b"v1".to_vec()));
+
+    set(&pool, "k", b"v2").unwrap();
+    assert_eq!(get(&pool, "k").unwrap(), Some(b"v2".to_vec()));
+
+    delete(&pool, "k").unwrap();
+    assert_eq!(get(&pool, "k").unwrap(), None);
+  }
+}
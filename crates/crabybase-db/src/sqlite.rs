@@ -1,11 +1,15 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
 use log::debug;
 use r2d2::Pool;
 use r2d2_sqlite::SqliteConnectionManager;
-use rusqlite::{Column, Row, Rows};
+use rusqlite::types::Type;
+use rusqlite::{Column, Connection, Row, Rows, Transaction};
 use rusqlite_migration::{Migrations, M};
 use serde::de::DeserializeOwned;
+use serde::Serialize;
 use serde_json::{json, Value};
+use serde_rusqlite::{from_row, to_params_named};
 
 pub type DBPool = Pool<SqliteConnectionManager>;
 
@@ -29,9 +33,14 @@ pub fn connect_data_pool() -> Result<DBPool> {
   let data_pool = connect_db("test.db")?;
   let mut data_connection = data_pool.get()?;
 
-  let migrations = Migrations::new(vec![M::up(include_str!(
-    "../migrations/sqlite/data/2023-04-08-create-tables-table.sql"
-  ))]);
+  let migrations = Migrations::new(vec![
+    M::up(include_str!(
+      "../migrations/sqlite/data/2023-04-08-create-tables-table.sql"
+    )),
+    M::up(include_str!(
+      "../migrations/sqlite/data/2026-07-30-create-objects-table.sql"
+    )),
+  ]);
   migrations.to_latest(&mut data_connection)?;
 
   Ok(data_pool)
@@ -42,14 +51,44 @@ pub fn connect_log_pool() -> Result<DBPool> {
   let log_pool = connect_db("log.db")?;
   let mut log_connection = log_pool.get()?;
 
-  let migrations = Migrations::new(vec![M::up(include_str!(
-    "../migrations/sqlite/log/2023-04-08-create-logs-table.sql"
-  ))]);
+  let migrations = Migrations::new(vec![
+    M::up(include_str!(
+      "../migrations/sqlite/log/2023-04-08-create-logs-table.sql"
+    )),
+    M::up(include_str!(
+      "../migrations/sqlite/log/2026-07-30-create-changesets-table.sql"
+    )),
+  ]);
   migrations.to_latest(&mut log_connection)?;
 
   Ok(log_pool)
 }
 
+pub fn execute<P: Serialize>(connection: &Connection, query: &str, params: &P) -> Result<usize> {
+  let named_params = to_params_named(params)?;
+  Ok(connection.execute(query, named_params.to_slice().as_slice())?)
+}
+
+pub fn transaction<T>(pool: &DBPool, run: impl FnOnce(&Transaction) -> Result<T>) -> Result<T> {
+  let mut connection = pool.get()?;
+  let tx = connection.transaction()?;
+  match run(&tx) {
+    Ok(value) => {
+      tx.commit()?;
+      Ok(value)
+    }
+    Err(err) => {
+      // Report the write's own error, not a rollback failure - losing the
+      // real cause behind an unrelated rollback error would make failed
+      // writes much harder to diagnose.
+      if let Err(rollback_err) = tx.rollback() {
+        log::warn!("Failed to roll back transaction after error {err}: {rollback_err}");
+      }
+      Err(err)
+    }
+  }
+}
+
 #[macro_export]
 macro_rules! query_rows_columns {
   ($columns:ident, $rows:ident, $connection:expr, $query:expr) => {
@@ -67,11 +106,10 @@ macro_rules! query_rows_columns {
   };
 }
 
-pub fn parse_rows<T: DeserializeOwned>(columns: &Vec<Column>, mut rows: Rows) -> Result<Vec<T>> {
+pub fn parse_rows<T: DeserializeOwned>(_columns: &Vec<Column>, mut rows: Rows) -> Result<Vec<T>> {
   let mut rows_data = vec![];
   while let Some(row) = rows.next()? {
-    let parsed_row = parse_row(&columns, row)?;
-    rows_data.push(serde_json::from_value::<T>(parsed_row)?);
+    rows_data.push(from_row::<T>(row)?);
   }
   Ok(rows_data)
 }
@@ -102,35 +140,284 @@ fn parse_column(row: &Row, column: &Column, index: usize) -> Result<Value> {
     Some("INTEGER") => parse_integer_column(row, index)?,
     Some("REAL") => parse_real_column(row, index)?,
     Some("BLOB") => parse_blob_column(row, index)?,
-    _ => panic!(
-      "Unknown column type {decl_type}",
-      decl_type = column.decl_type().unwrap_or_else(|| "UNKNOWN")
-    ),
+    Some("DATETIME") | Some("TIMESTAMP") => parse_datetime_column(row, index)?,
+    Some("BOOLEAN") => parse_boolean_column(row, index)?,
+    // NUMERIC affinity can end up stored as either an INTEGER or a REAL,
+    // so just defer to whatever SQLite actually wrote for this value.
+    Some("NUMERIC") => parse_column_by_runtime_type(row, index)?,
+    // No declared type (expressions, COUNT(*), views, ...) - fall back to
+    // SQLite's runtime type affinity for this particular value instead of
+    // blowing up on a perfectly valid `SELECT`.
+    _ => parse_column_by_runtime_type(row, index)?,
   })
 }
 
+fn parse_column_by_runtime_type(row: &Row, index: usize) -> Result<Value> {
+  Ok(match row.get_ref(index)?.data_type() {
+    Type::Null => Value::Null,
+    Type::Integer => parse_integer_column(row, index)?,
+    Type::Real => parse_real_column(row, index)?,
+    Type::Text => parse_text_column(row, index)?,
+    Type::Blob => parse_blob_column(row, index)?,
+  })
+}
+
+fn is_null(row: &Row, index: usize) -> Result<bool> {
+  Ok(row.get_ref(index)?.data_type() == Type::Null)
+}
+
 fn parse_json_column(row: &Row, index: usize) -> Result<Value> {
+  if is_null(row, index)? {
+    return Ok(Value::Null);
+  }
   let json_string: String = row.get(index)?;
   let parsed: Value = serde_json::from_str(&json_string)?;
   Ok(parsed)
 }
 
 fn parse_text_column(row: &Row, index: usize) -> Result<Value> {
+  if is_null(row, index)? {
+    return Ok(Value::Null);
+  }
   let text: String = row.get(index)?;
   Ok(json!(text))
 }
 
 fn parse_integer_column(row: &Row, index: usize) -> Result<Value> {
+  if is_null(row, index)? {
+    return Ok(Value::Null);
+  }
   let integer: i64 = row.get(index)?;
   Ok(json!(integer))
 }
 
 fn parse_real_column(row: &Row, index: usize) -> Result<Value> {
+  if is_null(row, index)? {
+    return Ok(Value::Null);
+  }
   let real: f64 = row.get(index)?;
   Ok(json!(real))
 }
 
 fn parse_blob_column(row: &Row, index: usize) -> Result<Value> {
+  if is_null(row, index)? {
+    return Ok(Value::Null);
+  }
   let blob: Vec<u8> = row.get(index)?;
   Ok(json!(blob))
 }
+
+fn parse_datetime_column(row: &Row, index: usize) -> Result<Value> {
+  if is_null(row, index)? {
+    return Ok(Value::Null);
+  }
+  let datetime = match row.get_ref(index)?.data_type() {
+    Type::Integer => {
+      let epoch_seconds: i64 = row.get(index)?;
+      DateTime::from_timestamp(epoch_seconds, 0)
+        .ok_or_else(|| anyhow!("invalid unix timestamp {epoch_seconds}"))?
+    }
+    Type::Real => {
+      let julian_day: f64 = row.get(index)?;
+      julian_day_to_datetime(julian_day)?
+    }
+    _ => {
+      let text: String = row.get(index)?;
+      parse_sqlite_datetime_text(&text)?
+    }
+  };
+  Ok(json!(datetime.to_rfc3339()))
+}
+
+fn julian_day_to_datetime(julian_day: f64) -> Result<DateTime<Utc>> {
+  const UNIX_EPOCH_JULIAN_DAY: f64 = 2_440_587.5;
+  // Round rather than truncate - the subtraction/multiplication below loses
+  // enough precision that truncating can land a full second early.
+  let unix_seconds = ((julian_day - UNIX_EPOCH_JULIAN_DAY) * 86_400.0).round() as i64;
+  DateTime::from_timestamp(unix_seconds, 0)
+    .ok_or_else(|| anyhow!("invalid julian day {julian_day}"))
+}
+
+fn parse_sqlite_datetime_text(text: &str) -> Result<DateTime<Utc>> {
+  if let Ok(datetime) = DateTime::parse_from_rfc3339(text) {
+    return Ok(datetime.with_timezone(&Utc));
+  }
+  for format in ["%Y-%m-%d %H:%M:%S%.f", "%Y-%m-%dT%H:%M:%S%.f"] {
+    if let Ok(naive) = NaiveDateTime::parse_from_str(text, format) {
+      return Ok(naive.and_utc());
+    }
+  }
+  if let Ok(date) = NaiveDate::parse_from_str(text, "%Y-%m-%d") {
+    return Ok(date.and_hms_opt(0, 0, 0).unwrap().and_utc());
+  }
+  Err(anyhow!("unrecognized SQLite datetime value {text:?}"))
+}
+
+fn parse_boolean_column(row: &Row, index: usize) -> Result<Value> {
+  if is_null(row, index)? {
+    return Ok(Value::Null);
+  }
+  let integer: i64 = row.get(index)?;
+  Ok(json!(integer != 0))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use rusqlite::Connection;
+
+  fn first_row_value(connection: &Connection, query: &str) -> Value {
+    let statement = connection.prepare(query).unwrap();
+    let columns = statement.columns();
+    let mut statement = connection.prepare(query).unwrap();
+    let mut rows = statement.query([]).unwrap();
+    let row = rows.next().unwrap().unwrap();
+    parse_row(&columns, row).unwrap()
+  }
+
+  #[test]
+  fn parse_column_emits_null_for_null_values() {
+    let connection = Connection::open_in_memory().unwrap();
+    connection
+      .execute_batch("CREATE TABLE t (value TEXT)")
+      .unwrap();
+    connection
+      .execute("INSERT INTO t (value) VALUES (NULL)", [])
+      .unwrap();
+
+    let value = first_row_value(&connection, "SELECT value FROM t");
+    assert_eq!(value["value"], Value::Null);
+  }
+
+  #[test]
+  fn parse_column_falls_back_to_runtime_type_when_decl_type_is_missing() {
+    let connection = Connection::open_in_memory().unwrap();
+    connection
+      .execute_batch("CREATE TABLE t (value INTEGER)")
+      .unwrap();
+
+    let value = first_row_value(&connection, "SELECT COUNT(*) AS total FROM t WHERE 1 = 0");
+    assert_eq!(value["total"], json!(0));
+  }
+
+  #[test]
+  fn parse_column_normalizes_datetime_representations_to_rfc3339() {
+    let connection = Connection::open_in_memory().unwrap();
+    connection
+      .execute_batch(
+        "CREATE TABLE t (
+          text_value DATETIME,
+          epoch_value DATETIME,
+          julian_value DATETIME
+        )",
+      )
+      .unwrap();
+    connection
+      .execute(
+        "INSERT INTO t (text_value, epoch_value, julian_value)
+         VALUES ('2024-01-02 03:04:05', 1704164645, 2460311.627835648)",
+        [],
+      )
+      .unwrap();
+
+    let value = first_row_value(&connection, "SELECT text_value FROM t");
+    assert_eq!(value["text_value"], json!("2024-01-02T03:04:05+00:00"));
+
+    let value = first_row_value(&connection, "SELECT epoch_value FROM t");
+    assert_eq!(value["epoch_value"], json!("2024-01-02T03:04:05+00:00"));
+
+    let value = first_row_value(&connection, "SELECT julian_value FROM t");
+    assert_eq!(value["julian_value"], json!("2024-01-02T03:04:05+00:00"));
+  }
+
+  #[test]
+  fn parse_column_reads_boolean_as_json_bool() {
+    let connection = Connection::open_in_memory().unwrap();
+    connection
+      .execute_batch("CREATE TABLE t (active BOOLEAN)")
+      .unwrap();
+    connection
+      .execute("INSERT INTO t (active) VALUES (1)", [])
+      .unwrap();
+
+    let value = first_row_value(&connection, "SELECT active FROM t");
+    assert_eq!(value["active"], json!(true));
+  }
+
+  #[derive(serde::Serialize)]
+  struct NewItem {
+    id: i64,
+    name: String,
+  }
+
+  #[test]
+  fn execute_round_trips_a_struct_into_named_params() {
+    let connection = Connection::open_in_memory().unwrap();
+    connection
+      .execute_batch("CREATE TABLE items (id INTEGER PRIMARY KEY, name TEXT)")
+      .unwrap();
+
+    let affected = execute(
+      &connection,
+      "INSERT INTO items (id, name) VALUES (:id, :name)",
+      &NewItem {
+        id: 1,
+        name: "widget".to_string(),
+      },
+    )
+    .unwrap();
+    assert_eq!(affected, 1);
+
+    let value = first_row_value(&connection, "SELECT name FROM items WHERE id = 1");
+    assert_eq!(value["name"], json!("widget"));
+  }
+
+  fn memory_pool() -> DBPool {
+    let manager = r2d2_sqlite::SqliteConnectionManager::memory();
+    r2d2::Pool::builder().max_size(1).build(manager).unwrap()
+  }
+
+  #[test]
+  fn transaction_commits_on_ok() {
+    let pool = memory_pool();
+    pool
+      .get()
+      .unwrap()
+      .execute_batch("CREATE TABLE t (id INTEGER PRIMARY KEY)")
+      .unwrap();
+
+    transaction(&pool, |tx| {
+      tx.execute("INSERT INTO t (id) VALUES (1)", [])?;
+      Ok(())
+    })
+    .unwrap();
+
+    let connection = pool.get().unwrap();
+    let count: i64 = connection
+      .query_row("SELECT COUNT(*) FROM t", [], |row| row.get(0))
+      .unwrap();
+    assert_eq!(count, 1);
+  }
+
+  #[test]
+  fn transaction_rolls_back_on_err() {
+    let pool = memory_pool();
+    pool
+      .get()
+      .unwrap()
+      .execute_batch("CREATE TABLE t (id INTEGER PRIMARY KEY)")
+      .unwrap();
+
+    let result: Result<()> = transaction(&pool, |tx| {
+      tx.execute("INSERT INTO t (id) VALUES (1)", [])?;
+      Err(anyhow!("boom"))
+    });
+    assert!(result.is_err());
+
+    let connection = pool.get().unwrap();
+    let count: i64 = connection
+      .query_row("SELECT COUNT(*) FROM t", [], |row| row.get(0))
+      .unwrap();
+    assert_eq!(count, 0);
+  }
+}